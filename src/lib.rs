@@ -6,41 +6,126 @@
 //! Nodes are stored in a thread-local object pool and backed by a
 //! global, lock-free pool.
 //!
-//! NOTE: For efficiency and simplicity, this crate never deallocates
-//! nodes. If you expect to potentially store unbounded sets of
-//! Wakers, use a [std::vec::Vec].
+//! NOTE: For efficiency and simplicity, pooled nodes are never
+//! deallocated, not even by [set_pool_max] or [trim]: a [WakerKey]
+//! issued for any node may be presented to [WakerList::remove] at an
+//! arbitrary later time, and actually freeing the node out from under
+//! an outstanding key would turn that into a use-after-free. Those
+//! two only bound (and, for `trim`, shrink) how many excess nodes a
+//! pool holds onto for reuse; nodes evicted past the cap are
+//! abandoned rather than freed. By default the pool is unbounded and
+//! nothing is ever evicted either. If you expect to potentially store
+//! unbounded sets of Wakers, use a [std::vec::Vec].
 
 use core::cell::Cell;
 use core::mem::MaybeUninit;
 use core::ptr;
-use core::sync::atomic::AtomicPtr;
-use core::sync::atomic::Ordering;
 use core::task::Waker;
 
+mod notify;
+mod sync;
+
+pub use notify::Notify;
+
+use sync::static_atomic;
+use sync::thread_local;
+use sync::AtomicPtr;
+use sync::AtomicUsize;
+use sync::Ordering;
+
 type WakerNodePtr = AtomicPtr<WakerNode>;
 
+static_atomic! {
+    /// Soft cap on how many released nodes a single pool (a thread's
+    /// [LocalPool], or `GLOBAL_POOL`) is allowed to cache for reuse.
+    /// Checked only at the points nodes are released in bulk --
+    /// [LocalPool]'s `release_list` and `Drop` -- so the common
+    /// single-node release path (`pop`, `remove`) stays allocation-
+    /// and check-free. Defaults to effectively unbounded.
+    ///
+    /// Set with [set_pool_max]; see also [trim] to shrink a pool
+    /// already cached above a newly-lowered cap.
+    static POOL_MAX: AtomicUsize = usize::MAX;
+}
+
+/// Configures the soft cap described on [POOL_MAX]. Nodes released in
+/// bulk beyond the cap are abandoned instead of being cached; see the
+/// crate-level docs for why they aren't simply freed.
+pub fn set_pool_max(max: usize) {
+    POOL_MAX.store(max, Ordering::Relaxed);
+}
+
+static_atomic! {
+    /// Approximate count of nodes currently sitting in `GLOBAL_POOL`,
+    /// maintained alongside it so [trim] doesn't need to walk the
+    /// whole stack just to know how much is in it. Like `GLOBAL_POOL`
+    /// itself, it's only ever approximate under concurrent access: a
+    /// caller racing an `acquire_node`/release may see it over- or
+    /// under-counted by the size of that race.
+    static GLOBAL_POOL_LEN: AtomicUsize = 0;
+}
+
+/// Walks `node`'s chain via `next` and retires every node on it,
+/// without touching any pool. Returns the number of nodes retired.
+///
+/// "Retiring" a node does *not* deallocate it. A [WakerKey] issued
+/// for a node while it was in use may be presented to
+/// [WakerList::remove] at an arbitrary later time -- possibly long
+/// after the node has been released, reused, and released again --
+/// and `remove` validates it by dereferencing `key.node` before it
+/// even gets to compare generations. If that node had actually been
+/// freed in the meantime, that dereference would be a use-after-free.
+/// So a retired node is simply abandoned: it stops counting toward
+/// any pool's size and is never handed out again, but its allocation
+/// is deliberately leaked rather than given back, keeping every
+/// outstanding `WakerKey`'s generation check safe to perform no
+/// matter how long the key is held.
+unsafe fn retire_chain(mut node: *mut WakerNode) -> usize {
+    let mut retired = 0;
+    while !node.is_null() {
+        let next = unsafe { (*node).next };
+        // Deliberately not reconstructed into a `Box` and dropped --
+        // see the doc comment above.
+        node = next;
+        retired += 1;
+    }
+    retired
+}
+
 struct WakerNode {
+    prev: *mut WakerNode,
     next: *mut WakerNode,
+    // Bumped every time the node is released back to a pool. Lets a
+    // `WakerKey` captured before the release detect that the node has
+    // since been reused for something else, rather than unlinking (or
+    // reporting as present) a node it no longer has any claim to.
+    generation: u64,
     waker: MaybeUninit<Waker>,
 }
 
 fn allocate_node() -> *mut WakerNode {
     Box::into_raw(Box::new(WakerNode {
+        prev: ptr::null_mut(),
         next: ptr::null_mut(),
+        generation: 0,
         waker: MaybeUninit::uninit(),
     }))
 }
 
-static GLOBAL_POOL: WakerNodePtr = WakerNodePtr::new(ptr::null_mut());
+static_atomic! {
+    static GLOBAL_POOL: WakerNodePtr = ptr::null_mut();
+}
 
 struct LocalPool {
     head: Cell<*mut WakerNode>,
+    len: Cell<usize>,
 }
 
 impl LocalPool {
     const fn new() -> LocalPool {
         LocalPool {
             head: Cell::new(ptr::null_mut()),
+            len: Cell::new(0),
         }
     }
 
@@ -48,6 +133,7 @@ impl LocalPool {
         let node = self.head.get();
         if !node.is_null() {
             self.head.set(unsafe { (*node).next });
+            self.len.set(self.len.get() - 1);
             // We could clear the next pointer, but the caller is
             // responsible.
             return node;
@@ -58,7 +144,11 @@ impl LocalPool {
             if node.is_null() {
                 break;
             }
-            // No ABA on global pool because we never deallocate.
+            // No ABA from other `acquire_node` callers, or from a
+            // concurrent `trim`: neither ever deallocates a node, so
+            // dereferencing one to read `next` is always sound, even
+            // if it was just (or is about to be) popped by someone
+            // else.
             let new_head = unsafe { (*node).next };
             node = match GLOBAL_POOL.compare_exchange_weak(
                 node,
@@ -67,6 +157,7 @@ impl LocalPool {
                 Ordering::Acquire,
             ) {
                 Ok(popped) => {
+                    GLOBAL_POOL_LEN.fetch_sub(1, Ordering::Relaxed);
                     return popped;
                 }
                 Err(node) => node,
@@ -81,12 +172,30 @@ impl LocalPool {
             (*node).next = self.head.get();
             self.head.set(node);
         }
+        self.len.set(self.len.get() + 1);
     }
 
-    unsafe fn release_list(&self, head: *mut WakerNode) {
+    /// Appends the chain starting at `head` (of length `count`) to
+    /// this pool, first retiring however many of its *tail* nodes
+    /// would push this pool over [POOL_MAX]. The chain isn't visible
+    /// to any other thread yet, so trimming it here needs no
+    /// synchronization.
+    unsafe fn release_list(&self, head: *mut WakerNode, count: usize) {
+        let accept = POOL_MAX.load(Ordering::Relaxed).saturating_sub(self.len.get());
+        let accept = accept.min(count);
+        let head = if accept < count {
+            unsafe { truncate_chain(head, accept) }
+        } else {
+            head
+        };
+        if head.is_null() {
+            return;
+        }
+
         let mut p = self.head.get();
         if p.is_null() {
             self.head.set(head);
+            self.len.set(self.len.get() + accept);
             return;
         }
         loop {
@@ -97,16 +206,36 @@ impl LocalPool {
             p = next;
         }
         unsafe { (*p).next = head }
+        self.len.set(self.len.get() + accept);
     }
-}
 
-impl Drop for LocalPool {
-    fn drop(&mut self) {
-        let mut p = self.head.get();
-        if p.is_null() {
+    /// Moves this pool's nodes to `GLOBAL_POOL`, first retiring
+    /// however many would push the global pool over [POOL_MAX]. Pulled
+    /// out of `Drop` so it can also be called explicitly; see `Drop`
+    /// for why that matters under loom.
+    fn migrate_to_global(&self) {
+        let mut head = self.head.replace(ptr::null_mut());
+        if head.is_null() {
             return;
         }
+
+        // This thread's nodes aren't reachable from `GLOBAL_POOL` yet,
+        // so retiring however many of them would push it over the cap
+        // needs no synchronization with other threads.
+        let local_len = self.len.replace(0);
+        let accept = POOL_MAX
+            .load(Ordering::Relaxed)
+            .saturating_sub(GLOBAL_POOL_LEN.load(Ordering::Relaxed))
+            .min(local_len);
+        if accept < local_len {
+            head = unsafe { truncate_chain(head, accept) };
+        }
+        if head.is_null() {
+            return;
+        }
+
         // Find the tail.
+        let mut p = head;
         loop {
             let next = unsafe { (*p).next };
             if next.is_null() {
@@ -122,31 +251,175 @@ impl Drop for LocalPool {
             }
             global_head = match GLOBAL_POOL.compare_exchange_weak(
                 global_head,
-                self.head.get(),
+                head,
                 Ordering::AcqRel,
                 Ordering::Acquire,
             ) {
-                Ok(_) => return,
+                Ok(_) => {
+                    GLOBAL_POOL_LEN.fetch_add(accept, Ordering::Relaxed);
+                    return;
+                }
                 Err(node) => node,
             };
         }
     }
 }
 
+/// Walks `head`'s chain, keeping the first `keep` nodes and retiring
+/// the rest. Returns the (possibly unchanged) head, or null if `keep`
+/// is 0. Assumes the chain is not reachable from anywhere else.
+unsafe fn truncate_chain(head: *mut WakerNode, keep: usize) -> *mut WakerNode {
+    if keep == 0 {
+        unsafe { retire_chain(head) };
+        return ptr::null_mut();
+    }
+    let mut p = head;
+    for _ in 1..keep {
+        p = unsafe { (*p).next };
+    }
+    let rest = unsafe { (*p).next };
+    unsafe { (*p).next = ptr::null_mut() };
+    unsafe { retire_chain(rest) };
+    head
+}
+
+impl Drop for LocalPool {
+    fn drop(&mut self) {
+        // Touching a loom atomic from inside a `thread_local!`'s
+        // destructor panics inside loom 0.7.2's own runtime
+        // (`Atomic::rmw` indexing out of bounds during
+        // `thread_done`), regardless of what the atomic op actually
+        // is -- reproduces with a minimal `loom::lazy_static!` atomic
+        // touched from an unrelated thread-local's `Drop`, so it's a
+        // limitation of loom's thread-exit teardown, not a bug in the
+        // algorithm being modeled. Skip the migration under loom;
+        // tests that want to exercise it call `migrate_to_global`
+        // explicitly (via `drain_current_thread_pool_for_test`)
+        // before the thread exits instead.
+        #[cfg(not(loom))]
+        self.migrate_to_global();
+    }
+}
+
+/// Test-only hook that runs what `Drop for LocalPool` would otherwise
+/// do, without going through actual thread-exit teardown. Only
+/// exists so the loom suite can model `LocalPool::migrate_to_global`
+/// racing `trim`/`acquire_node`; see `Drop for LocalPool` for why it
+/// can't just let the thread exit instead.
+#[cfg(loom)]
+#[doc(hidden)]
+pub fn drain_current_thread_pool_for_test() {
+    LOCAL_POOL.with(LocalPool::migrate_to_global);
+}
+
+// loom's `thread_local!` doesn't support the `const { .. }` initializer
+// shorthand, so the two cfgs spell out the thread-local slightly
+// differently.
+#[cfg(not(loom))]
 thread_local! {
     static LOCAL_POOL: LocalPool = const { LocalPool::new() }
 }
+#[cfg(loom)]
+thread_local! {
+    static LOCAL_POOL: LocalPool = LocalPool::new()
+}
 
 fn acquire_node() -> *mut WakerNode {
     LOCAL_POOL.with(LocalPool::acquire_node)
 }
 
 unsafe fn release_node(node: *mut WakerNode) {
+    unsafe {
+        (*node).generation = (*node).generation.wrapping_add(1);
+    }
     LOCAL_POOL.with(|lp| unsafe { LocalPool::release_node(lp, node) })
 }
 
 unsafe fn release_list(head: *mut WakerNode) {
-    LOCAL_POOL.with(|lp| unsafe { LocalPool::release_list(lp, head) })
+    let mut count = 0;
+    unsafe {
+        let mut p = head;
+        while !p.is_null() {
+            (*p).generation = (*p).generation.wrapping_add(1);
+            p = (*p).next;
+            count += 1;
+        }
+    }
+    LOCAL_POOL.with(|lp| unsafe { LocalPool::release_list(lp, head, count) })
+}
+
+/// Shrinks `GLOBAL_POOL` down to `target` nodes, retiring the excess.
+///
+/// This only shrinks `GLOBAL_POOL` itself; nodes sitting in other
+/// threads' thread-local pools aren't touched until those threads
+/// exit (at which point [set_pool_max]'s cap applies to them too).
+///
+/// `trim` takes the whole global pool (via a swap, so it never blocks
+/// on or excludes anyone else) and publishes the kept portion back
+/// with the same CAS-retry `acquire_node` and `Drop for LocalPool`
+/// already use to touch `GLOBAL_POOL`, so a node pushed concurrently
+/// while `trim` had the pool swapped out is merged back in rather than
+/// clobbered.
+pub fn trim(target: usize) {
+    let head = GLOBAL_POOL.swap(ptr::null_mut(), Ordering::AcqRel);
+    if head.is_null() {
+        return;
+    }
+    if target == 0 {
+        let retired = unsafe { retire_chain(head) };
+        GLOBAL_POOL_LEN.fetch_sub(retired, Ordering::Relaxed);
+        return;
+    }
+
+    let mut tail = head;
+    let mut kept_len = 1;
+    while kept_len < target {
+        let next = unsafe { (*tail).next };
+        if next.is_null() {
+            break;
+        }
+        tail = next;
+        kept_len += 1;
+    }
+    let rest = unsafe { (*tail).next };
+    unsafe { (*tail).next = ptr::null_mut() };
+    let retired = unsafe { retire_chain(rest) };
+    GLOBAL_POOL_LEN.fetch_sub(retired, Ordering::Relaxed);
+
+    let mut global_head = GLOBAL_POOL.load(Ordering::Acquire);
+    loop {
+        unsafe {
+            (*tail).next = global_head;
+        }
+        global_head = match GLOBAL_POOL.compare_exchange_weak(
+            global_head,
+            head,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return,
+            Err(node) => node,
+        };
+    }
+}
+
+/// An opaque handle to a [Waker] previously stored in a [WakerList],
+/// returned by [WakerList::push]. Pass it to [WakerList::remove] to
+/// unlink and reclaim that specific waker in O(1) without waking it
+/// or disturbing the rest of the list.
+///
+/// A key is only valid for the node it was issued for. Once that node
+/// is released back to the pool (by [WakerList::pop], [WakerList::remove]
+/// itself, or the list being dropped), the key's generation no longer
+/// matches and [WakerList::remove] returns `None`, even if the node
+/// has since been reused by another [WakerList] -- or retired by
+/// [set_pool_max]/[trim], which evict nodes from a pool without ever
+/// deallocating them for exactly this reason: a key may be held and
+/// presented to `remove` arbitrarily long after it was issued.
+#[derive(Debug, Clone, Copy)]
+pub struct WakerKey {
+    node: *mut WakerNode,
+    generation: u64,
 }
 
 /// Stores a linked list of [core::task::Waker].
@@ -193,14 +466,23 @@ impl WakerList {
         self.head.is_null()
     }
 
-    /// Adds a [Waker] to the list.
-    pub fn push(&mut self, waker: Waker) {
+    /// Adds a [Waker] to the list, returning a [WakerKey] that can
+    /// later be passed to [WakerList::remove] to cancel it.
+    pub fn push(&mut self, waker: Waker) -> WakerKey {
         let node = acquire_node();
         unsafe {
             (*node).waker.write(waker);
+            (*node).prev = ptr::null_mut();
             (*node).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = node;
+            }
+            self.head = node;
+            WakerKey {
+                node,
+                generation: (*node).generation,
+            }
         }
-        self.head = node;
     }
 
     /// Pops a [Waker] from the back of the list. Returns [None] if
@@ -213,12 +495,173 @@ impl WakerList {
                 let node = self.head;
 
                 self.head = (*node).next;
+                if !self.head.is_null() {
+                    (*self.head).prev = ptr::null_mut();
+                }
                 let waker = (*node).waker.assume_init_read();
                 release_node(node);
                 waker
             })
         }
     }
+
+    /// Like [WakerList::push], but first scans up to `scan_depth`
+    /// nodes starting at the head, and if any of them stores a waker
+    /// that [Waker::will_wake] the incoming one, `waker` is dropped
+    /// instead of being stored. Returns `None` in that case, or the
+    /// new node's [WakerKey] otherwise.
+    ///
+    /// Following the same optimization as `AtomicWaker` (used by
+    /// tokio and futures-util), this keeps a future that's repeatedly
+    /// polled by the same executor (the common case) from growing the
+    /// list with duplicate clones of the same waker and churning the
+    /// pool.
+    pub fn push_dedup(&mut self, waker: Waker, scan_depth: usize) -> Option<WakerKey> {
+        unsafe {
+            let mut p = self.head;
+            let mut remaining = scan_depth;
+            while !p.is_null() && remaining > 0 {
+                if (*p).waker.assume_init_ref().will_wake(&waker) {
+                    return None;
+                }
+                p = (*p).next;
+                remaining -= 1;
+            }
+        }
+        Some(self.push(waker))
+    }
+
+    /// Removes and returns the [Waker] identified by `key` without
+    /// waking it, in O(1) regardless of where in the list it is.
+    ///
+    /// Returns [None] if `key`'s node has already been released back
+    /// to the pool, whether because it was already removed, popped,
+    /// or the list containing it was dropped. This is always a sound
+    /// check to make, no matter how long `key` was held onto first:
+    /// see [WakerKey] for why the node it points to is never actually
+    /// deallocated.
+    pub fn remove(&mut self, key: WakerKey) -> Option<Waker> {
+        unsafe {
+            if (*key.node).generation != key.generation {
+                return None;
+            }
+
+            let node = key.node;
+            let prev = (*node).prev;
+            let next = (*node).next;
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+
+            let waker = (*node).waker.assume_init_read();
+            release_node(node);
+            Some(waker)
+        }
+    }
+}
+
+/// A [Sync] counterpart to [WakerList] for futures, like broadcast
+/// channels, that are shared by many producers registering wakers
+/// concurrently. `push` is lock-free, using the same CAS-on-head
+/// technique as `GLOBAL_POOL`; nodes come from the same thread-local
+/// and global pool machinery as [WakerList].
+///
+/// The order in which concurrent pushes end up linked is unspecified.
+/// `drain` is single-consumer: callers are responsible for ensuring
+/// at most one drain happens at a time, since concurrent drains would
+/// each observe only part of the list.
+#[derive(Debug)]
+pub struct SharedWakerList {
+    head: WakerNodePtr,
+}
+
+// The whole point of this type is to be shared across threads.
+unsafe impl Send for SharedWakerList {}
+unsafe impl Sync for SharedWakerList {}
+
+impl Drop for SharedWakerList {
+    fn drop(&mut self) {
+        drop(self.drain());
+    }
+}
+
+impl Default for SharedWakerList {
+    fn default() -> Self {
+        SharedWakerList::new()
+    }
+}
+
+impl SharedWakerList {
+    /// Returns a new empty list.
+    // loom's `AtomicPtr::new` isn't `const`, unlike `core`'s.
+    #[cfg(not(loom))]
+    pub const fn new() -> SharedWakerList {
+        SharedWakerList {
+            head: WakerNodePtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Returns a new empty list.
+    #[cfg(loom)]
+    pub fn new() -> SharedWakerList {
+        SharedWakerList {
+            head: WakerNodePtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Adds a [Waker] to the list. Safe to call from arbitrarily many
+    /// threads concurrently.
+    pub fn push(&self, waker: Waker) {
+        let node = acquire_node();
+        unsafe {
+            (*node).waker.write(waker);
+        }
+
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            unsafe {
+                (*node).next = head;
+            }
+            head = match self.head.compare_exchange_weak(
+                head,
+                node,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return,
+                Err(head) => head,
+            };
+        }
+    }
+
+    /// Atomically swaps out the whole list, handing the captured
+    /// chain back as an ordinary [WakerList] ready to be woken or
+    /// iterated. Exactly-once draining (no concurrent callers) is the
+    /// caller's responsibility.
+    pub fn drain(&self) -> WakerList {
+        let head = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+
+        // Producers only ever link `next`, since they don't know
+        // their predecessor at push time. Thread the `prev` pointers
+        // through in this single-consumer pass so the result is a
+        // well-formed doubly-linked WakerList.
+        unsafe {
+            let mut prev = ptr::null_mut();
+            let mut node = head;
+            while !node.is_null() {
+                (*node).prev = prev;
+                prev = node;
+                node = (*node).next;
+            }
+        }
+
+        WakerList { head }
+    }
 }
 
 /// To avoid WakerList needing to track the list's tail, iteration is
@@ -0,0 +1,96 @@
+//! A small `Notify`-like synchronization building block on top of
+//! [SharedWakerList], inspired by tokio's broadcast channel using an
+//! `AtomicBool` to skip re-registering and re-waking once a
+//! notification is already pending.
+
+use core::task::Waker;
+
+use crate::sync::AtomicBool;
+use crate::sync::Ordering;
+use crate::SharedWakerList;
+
+/// A one-shot, level-triggered notification. Once [Notify::notify] is
+/// called, the notification is latched: every task that registers
+/// afterward (or has already registered) observes it and is expected
+/// to treat its poll as `Ready`, without `Notify` storing or waking
+/// anything further.
+///
+/// Unlike tokio's `Notify`, which is edge-triggered per waiter and
+/// resets after each wakeup, this is a single sticky flag: it's meant
+/// for "this has happened, stop waiting" signals (e.g. shutdown or
+/// close), not for repeated wake cycles.
+#[derive(Debug, Default)]
+pub struct Notify {
+    wakers: SharedWakerList,
+    notified: AtomicBool,
+}
+
+impl Notify {
+    /// Returns a new, not-yet-notified `Notify`.
+    // `SharedWakerList::new` isn't `const` under loom (see its doc).
+    #[cfg(not(loom))]
+    pub const fn new() -> Notify {
+        Notify {
+            wakers: SharedWakerList::new(),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a new, not-yet-notified `Notify`.
+    #[cfg(loom)]
+    pub fn new() -> Notify {
+        Notify {
+            wakers: SharedWakerList::new(),
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if [Notify::notify] has already been called.
+    pub fn is_notified(&self) -> bool {
+        self.notified.load(Ordering::Acquire)
+    }
+
+    /// Registers `waker` to be woken by a future [Notify::notify].
+    ///
+    /// Returns `true` if the notification has already landed, in
+    /// which case `waker` is not left stored (if it was pushed while
+    /// the notification was landing, it has already been drained and
+    /// woken) and the caller should treat this poll as `Ready`
+    /// immediately.
+    pub fn register(&self, waker: &Waker) -> bool {
+        if self.is_notified() {
+            return true;
+        }
+        self.wakers.push(waker.clone());
+        // `notify` may have landed concurrently between the check
+        // above and the push, and its drain may have missed this
+        // one. Draining again here picks up the clone just pushed if
+        // no other drain (`notify`'s, or a concurrent `register`'s)
+        // has taken it already, along with anything else pushed in
+        // the meantime, instead of leaving it stranded until `self`
+        // itself is dropped. Each drain swaps out a disjoint part of
+        // the list, so every stored waker is woken exactly once.
+        if self.is_notified() {
+            for waker in self.wakers.drain() {
+                waker.wake();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Flips the notified flag and wakes every registered waker.
+    ///
+    /// A flood of concurrent `notify()` calls collapses to a single
+    /// stored-state transition: only the call that actually flips the
+    /// flag from false to true drains and wakes the list.
+    pub fn notify(&self) {
+        if self.notified.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        for waker in self.wakers.drain() {
+            waker.wake();
+        }
+    }
+}
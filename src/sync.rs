@@ -0,0 +1,57 @@
+//! Indirection over the primitives that `loom` needs to intercept in
+//! order to model-check the pool's lock-free algorithms.
+//!
+//! Under normal compilation this is just `core`/`std`. When built with
+//! `--cfg loom` (see tokio's `loom` integration for prior art), the
+//! loom-provided equivalents are used instead so that the model
+//! checker can explore thread interleavings of the atomic operations.
+//! Thread-local destruction is not modeled: `Drop for LocalPool`
+//! skips its migration under loom.
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicBool;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicPtr;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicUsize;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::Ordering;
+#[cfg(not(loom))]
+pub(crate) use std::thread_local;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicBool;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicPtr;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicUsize;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::Ordering;
+#[cfg(loom)]
+pub(crate) use loom::thread_local;
+
+// loom's atomics don't have a `const fn new`, since loom needs to
+// register them with the model checker's execution context at
+// runtime. A plain `static FOO: AtomicUsize = AtomicUsize::new(0)`
+// therefore only compiles under cfg(not(loom)); under loom the
+// equivalent is `loom::lazy_static!`, which this macro hides behind
+// the same declaration syntax used everywhere else in the crate.
+#[cfg(not(loom))]
+macro_rules! static_atomic {
+    ($(#[$meta:meta])* static $name:ident : $ty:ty = $init:expr;) => {
+        $(#[$meta])*
+        static $name: $ty = <$ty>::new($init);
+    };
+}
+
+#[cfg(loom)]
+macro_rules! static_atomic {
+    ($(#[$meta:meta])* static $name:ident : $ty:ty = $init:expr;) => {
+        loom::lazy_static! {
+            $(#[$meta])*
+            static ref $name: $ty = <$ty>::new($init);
+        }
+    };
+}
+
+pub(crate) use static_atomic;
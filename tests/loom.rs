@@ -0,0 +1,163 @@
+//! Model-checks the interleavings of the lock-free `GLOBAL_POOL` CAS
+//! loop, `LocalPool::migrate_to_global`, and `release_list` that the
+//! `stress` test in `unit.rs` can only sample randomly. Run with:
+//!
+//!     RUSTFLAGS="--cfg loom" cargo test --test loom --release
+//!
+//! This file compiles to nothing unless built with `--cfg loom`,
+//! since loom's exhaustive exploration is far too slow to run as
+//! part of a normal `cargo test`.
+#![cfg(loom)]
+
+use loom::thread;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Wake;
+use std::task::Waker;
+use wakerpool::WakerList;
+
+// `std::task::Wake`/`Waker::from` require a `std::sync::Arc`, not
+// loom's, so the waker itself is plain `std`; only the thread spawning
+// and the CAS logic it exercises (behind `crate::sync`) are loom's.
+struct CountingWaker {
+    woken: AtomicUsize,
+}
+
+impl CountingWaker {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            woken: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.woken.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// loom's state space explodes with iteration count, so this only
+// exercises a single push+pop per thread rather than `unit.rs`'s
+// thousands of iterations.
+#[test]
+fn two_threads_acquire_write_release() {
+    loom::model(|| {
+        let task: Arc<CountingWaker> = CountingWaker::new();
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let task = task.clone();
+                thread::spawn(move || {
+                    let waker: Waker = task.into();
+                    let mut wl = WakerList::new();
+                    wl.push(waker);
+                    // Exercises the thread-local pool acquiring a
+                    // node, racing the other thread's read of
+                    // GLOBAL_POOL, then the node being returned to the
+                    // thread-local pool (via `pop`). `Drop for
+                    // LocalPool` is a no-op under `cfg(loom)`, so the
+                    // node never reaches GLOBAL_POOL here; see
+                    // `thread_exit_drains_local_pool_to_global`.
+                    wl.pop().unwrap().wake();
+                })
+            })
+            .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        // Every waker pushed was popped and woken exactly once: no
+        // node was lost (which would leak the waker) and none was
+        // double-owned (which would let both threads observe the
+        // same node and double-wake or UB on drop).
+        assert_eq!(2, task.woken.load(Ordering::Relaxed));
+    });
+}
+
+// Drives a node through the full lifecycle this crate relies on:
+// acquired from (empty) GLOBAL_POOL, released to the thread-local
+// pool by `pop`, then pushed to GLOBAL_POOL by
+// `drain_current_thread_pool_for_test`, racing a second thread's
+// `acquire_node` pulling from GLOBAL_POOL concurrently.
+//
+// This calls the test-only hook instead of just letting the thread
+// exit and relying on `Drop for LocalPool`: touching a loom atomic
+// from inside a `thread_local!`'s destructor during actual
+// thread-exit teardown panics inside loom 0.7.2's own runtime,
+// regardless of what this crate's `Drop for LocalPool` actually does
+// -- reproduces with a minimal loom atomic touched from an unrelated
+// thread-local's `Drop`, so it's a loom limitation, not a bug here.
+// `Drop for LocalPool` is a no-op under `cfg(loom)` for this reason;
+// see its doc comment.
+#[test]
+fn thread_exit_drains_local_pool_to_global() {
+    loom::model(|| {
+        let task = CountingWaker::new();
+
+        let first = {
+            let task = task.clone();
+            thread::spawn(move || {
+                let waker: Waker = task.into();
+                let mut wl = WakerList::new();
+                wl.push(waker);
+                wl.pop().unwrap().wake();
+                // `LocalPool` for this thread now owns one free
+                // node; hand it to GLOBAL_POOL explicitly (see the
+                // comment above for why not just let the thread
+                // exit).
+                wakerpool::drain_current_thread_pool_for_test();
+            })
+        };
+
+        let second = {
+            let task = task.clone();
+            thread::spawn(move || {
+                let waker: Waker = task.into();
+                let mut wl = WakerList::new();
+                wl.push(waker);
+                wl.pop().unwrap().wake();
+            })
+        };
+
+        first.join().unwrap();
+        second.join().unwrap();
+    });
+}
+
+// Races `drain_current_thread_pool_for_test` publishing a thread's
+// freed nodes to `GLOBAL_POOL` against a concurrent `trim`, which
+// swaps `GLOBAL_POOL` out to null before publishing the (possibly
+// truncated) result back via the same CAS-retry merge `migrate_to_global`
+// uses. There's no public way to assert the exiting thread's node
+// wasn't lost (see the TODO on `drop_list_with_waker` in `unit.rs`),
+// so this only checks that the race doesn't panic or deadlock under
+// every interleaving loom explores.
+#[test]
+fn thread_exit_races_trim() {
+    loom::model(|| {
+        let task = CountingWaker::new();
+
+        let exiting = {
+            let task = task.clone();
+            thread::spawn(move || {
+                let waker: Waker = task.into();
+                let mut wl = WakerList::new();
+                wl.push(waker);
+                wl.pop().unwrap().wake();
+                // This thread's `LocalPool` now owns one free node;
+                // publishing it races the `trim` below.
+                wakerpool::drain_current_thread_pool_for_test();
+            })
+        };
+
+        let trimming = thread::spawn(|| {
+            wakerpool::trim(0);
+        });
+
+        exiting.join().unwrap();
+        trimming.join().unwrap();
+    });
+}
@@ -4,6 +4,8 @@ use core::task::Waker;
 use rand::Rng;
 use std::sync::Arc;
 use std::thread;
+use wakerpool::Notify;
+use wakerpool::SharedWakerList;
 use wakerpool::WakerList;
 
 struct Task {
@@ -38,6 +40,7 @@ fn marker_traits() {
     use static_assertions::assert_not_impl_any;
     assert_impl_all!(WakerList: Send, Unpin);
     assert_not_impl_any!(WakerList: Sync);
+    assert_impl_all!(SharedWakerList: Send, Sync, Unpin);
 }
 
 #[test]
@@ -83,6 +86,227 @@ fn drop_list_on_another_thread() {
     wl.push(task.waker());
 }
 
+#[test]
+fn remove_cancels_without_waking() {
+    let task = Task::new();
+
+    let mut wl = WakerList::new();
+    let key = wl.push(task.waker());
+    let waker = wl.remove(key).unwrap();
+
+    assert_eq!(0, task.wake_count());
+    assert!(wl.is_empty());
+    drop(waker);
+}
+
+#[test]
+fn remove_unlinks_middle_node() {
+    let task = Task::new();
+
+    let mut wl = WakerList::new();
+    wl.push(task.waker());
+    let middle = wl.push(task.waker());
+    wl.push(task.waker());
+
+    assert!(wl.remove(middle).is_some());
+
+    let mut remaining = 0;
+    while let Some(waker) = wl.pop() {
+        waker.wake();
+        remaining += 1;
+    }
+    assert_eq!(2, remaining);
+    assert_eq!(2, task.wake_count());
+}
+
+#[test]
+fn remove_with_stale_key_returns_none() {
+    let task = Task::new();
+
+    let mut wl = WakerList::new();
+    let key = wl.push(task.waker());
+    wl.pop().unwrap().wake();
+
+    assert!(wl.remove(key).is_none());
+}
+
+#[test]
+fn shared_push_and_drain() {
+    let task = Task::new();
+
+    let shared = SharedWakerList::new();
+    shared.push(task.waker());
+    shared.push(task.waker());
+
+    let mut drained = shared.drain();
+    assert!(!drained.is_empty());
+    while let Some(waker) = drained.pop() {
+        waker.wake();
+    }
+    assert_eq!(2, task.wake_count());
+
+    // A second drain of an already-drained list is empty.
+    assert!(shared.drain().is_empty());
+}
+
+#[test]
+fn shared_push_from_many_threads() {
+    let task = Task::new();
+    let shared = Arc::new(SharedWakerList::new());
+
+    let thread_count = thread::available_parallelism().unwrap().get();
+    let mut jh = Vec::with_capacity(thread_count);
+    for _ in 0..thread_count {
+        let task = task.clone();
+        let shared = shared.clone();
+        jh.push(thread::spawn(move || {
+            shared.push(task.waker());
+        }));
+    }
+    for h in jh {
+        h.join().unwrap();
+    }
+
+    let mut woken = 0;
+    for waker in shared.drain() {
+        waker.wake();
+        woken += 1;
+    }
+    assert_eq!(thread_count as u64, woken);
+    assert_eq!(thread_count as u64, task.wake_count());
+}
+
+#[test]
+fn notify_wakes_registered_waker() {
+    let task = Task::new();
+
+    let notify = Notify::new();
+    assert!(!notify.register(&task.waker()));
+
+    notify.notify();
+
+    assert_eq!(1, task.wake_count());
+}
+
+#[test]
+fn notify_before_register_returns_ready_immediately() {
+    let task = Task::new();
+
+    let notify = Notify::new();
+    notify.notify();
+
+    // The waker was never stored, so it's never woken, but the
+    // caller is told to treat this poll as Ready.
+    assert!(notify.register(&task.waker()));
+    assert_eq!(0, task.wake_count());
+}
+
+#[test]
+fn notify_flood_collapses_to_single_transition() {
+    let task = Task::new();
+
+    let notify = Notify::new();
+    notify.register(&task.waker());
+
+    notify.notify();
+    notify.notify();
+    notify.notify();
+
+    assert_eq!(1, task.wake_count());
+}
+
+#[test]
+fn push_dedup_drops_matching_waker() {
+    let task = Task::new();
+
+    let mut wl = WakerList::new();
+    wl.push(task.waker());
+    assert!(wl.push_dedup(task.waker(), 4).is_none());
+
+    let mut count = 0;
+    while let Some(waker) = wl.pop() {
+        waker.wake();
+        count += 1;
+    }
+    assert_eq!(1, count);
+    assert_eq!(1, task.wake_count());
+}
+
+#[test]
+fn push_dedup_stores_distinct_waker() {
+    let task_a = Task::new();
+    let task_b = Task::new();
+
+    let mut wl = WakerList::new();
+    wl.push(task_a.waker());
+    assert!(wl.push_dedup(task_b.waker(), 4).is_some());
+
+    let mut count = 0;
+    while let Some(waker) = wl.pop() {
+        waker.wake();
+        count += 1;
+    }
+    assert_eq!(2, count);
+}
+
+#[test]
+fn push_dedup_respects_scan_depth() {
+    let task = Task::new();
+
+    let mut wl = WakerList::new();
+    wl.push(task.waker());
+    // task's waker is now 1 node deep; a scan depth of 0 never looks
+    // at it, so the duplicate is stored rather than dropped.
+    assert!(wl.push_dedup(task.waker(), 0).is_some());
+}
+
+#[test]
+fn pool_max_and_trim_dont_disturb_behavior() {
+    // There's no public way to observe how many nodes the pool is
+    // actually holding onto (see the TODO on `drop_list_with_waker`),
+    // so this only checks that capping and trimming the pool don't
+    // break ordinary push/pop/wake behavior.
+    wakerpool::set_pool_max(1);
+
+    let task = Task::new();
+    for _ in 0..8 {
+        let mut wl = WakerList::new();
+        wl.push(task.waker());
+        wl.push(task.waker());
+        wl.pop().unwrap().wake();
+        wl.pop().unwrap().wake();
+    }
+    assert_eq!(16, task.wake_count());
+
+    wakerpool::trim(0);
+
+    let mut wl = WakerList::new();
+    wl.push(task.waker());
+    wl.pop().unwrap().wake();
+    assert_eq!(17, task.wake_count());
+
+    wakerpool::set_pool_max(usize::MAX);
+}
+
+#[test]
+fn remove_after_trim_is_safe() {
+    // A WakerKey stays valid (if stale) for as long as it's held, even
+    // if the node it names has since been evicted past the pool cap:
+    // trim/set_pool_max must retire such nodes rather than actually
+    // freeing them, or this would be a use-after-free.
+    wakerpool::set_pool_max(0);
+
+    let task = Task::new();
+    let mut wl = WakerList::new();
+    let key = wl.push(task.waker());
+    drop(wl);
+    wakerpool::trim(0);
+
+    assert!(WakerList::new().remove(key).is_none());
+
+    wakerpool::set_pool_max(usize::MAX);
+}
+
 #[test]
 fn stress() {
     const I: usize = if cfg!(miri) { 100 } else { 100000 };